@@ -1,12 +1,14 @@
+#![feature(allocator_api)]
+
 pub mod mem {
     use std::alloc;
-    use std::alloc::{GlobalAlloc, Layout, System};
-    use std::cell::UnsafeCell;
+    use std::alloc::{AllocError, Allocator, GlobalAlloc, Layout, System};
+    use std::cell::{RefCell, UnsafeCell};
     use std::fmt::{write, Display, Formatter};
-    use std::ptr::null_mut;
+    use std::ptr::{null_mut, NonNull};
     use std::sync::atomic::{
-        AtomicUsize,
-        Ordering::{Acquire, SeqCst},
+        AtomicU64, AtomicUsize,
+        Ordering::{Acquire, AcqRel, Relaxed, Release, SeqCst},
     };
 
     #[derive(Copy, Clone, Debug)]
@@ -16,6 +18,75 @@ pub mod mem {
         System,
     }
 
+    // Maximum nesting depth of `Janitor` guards on a single thread.
+    const CONTEXT_STACK_DEPTH: usize = 1024;
+
+    // A fixed-capacity stack of active contexts, one per thread. Deliberately *not* a `Vec`:
+    // growing a `Vec` can allocate, and that allocation would itself need to read this very
+    // stack to pick a context, recursing back into the allocator that's in the middle of
+    // initialising it. A fixed array sidesteps the reentrancy entirely, the same way the
+    // original single shared `ctx: [AllocationContext; 1024]` array did.
+    struct ContextStack {
+        ctx: [AllocationContext; CONTEXT_STACK_DEPTH],
+        len: usize,
+    }
+
+    impl ContextStack {
+        const fn new() -> Self {
+            Self {
+                ctx: [AllocationContext::System; CONTEXT_STACK_DEPTH],
+                len: 0,
+            }
+        }
+
+        fn push(&mut self, ctx: AllocationContext) {
+            self.ctx[self.len] = ctx;
+            self.len += 1;
+        }
+
+        fn pop(&mut self) {
+            self.len -= 1;
+        }
+
+        fn top(&self) -> Option<AllocationContext> {
+            self.len.checked_sub(1).map(|top| self.ctx[top])
+        }
+    }
+
+    thread_local! {
+        // Each thread gets its own independent stack of active contexts, so two threads
+        // entering `Janitor`s concurrently can never clobber one another's top-of-stack.
+        // The three backing allocators below remain shared across threads.
+        //
+        // The `const { .. }` initialiser is load-bearing: it's what lets the compiler give this
+        // thread local the "fast", non-lazy storage, so the very first push on a thread doesn't
+        // itself need to allocate.
+        static CONTEXT_STACK: RefCell<ContextStack> = const { RefCell::new(ContextStack::new()) };
+
+        // A thread-local byte whose address we use as a cheap, stable-Rust per-thread id (see
+        // `ArenaAllocator::claim_owner`): `ThreadId` has no stable `as_u64`, but every thread's
+        // copy of this token lives at its own address for the thread's whole lifetime.
+        static THREAD_TOKEN: u8 = 0;
+    }
+
+    // A per-thread id with no two live threads ever sharing a value, built from the address of
+    // this thread's own `THREAD_TOKEN` slot. Never zero, since it's a real address.
+    fn current_thread_token() -> u64 {
+        THREAD_TOKEN.with(|token| token as *const u8 as u64)
+    }
+
+    // Racily bumps `peak` up to `candidate` via a CAS-max loop; used to track each context's
+    // bytes-in-use high-water mark without a lock.
+    fn update_peak(peak: &AtomicUsize, candidate: usize) {
+        let mut current = peak.load(Relaxed);
+        while candidate > current {
+            match peak.compare_exchange_weak(current, candidate, AcqRel, Relaxed) {
+                Ok(_) => break,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
     #[derive(Debug)]
     struct SystemAllocator {
         allocated: AtomicUsize,
@@ -39,14 +110,85 @@ pub mod mem {
     const ARENA_SIZE: usize = 128 * 1024;
     const ARENA_MAX_SUPPORTED_ALIGN: usize = 4096;
 
+    // Sentinel for `last_alloc_offset` meaning "nothing has been allocated yet".
+    const ARENA_NO_LAST_ALLOC: usize = usize::MAX;
+
+    /// A bump (region) allocator over its own private 128 KiB buffer. Unlike the one backing
+    /// `#[global_allocator]`, this one is a standalone value: construct it with [`Self::new`]
+    /// and pass it to `Vec::new_in`/`Box::new_in` (etc.) to confine a collection to the region
+    /// without hijacking global allocation.
     #[derive(Debug)]
     #[repr(C, align(4096))] // 4096 == MAX_SUPPORTED_ALIGN
-    struct ArenaAllocator {
+    pub struct ArenaAllocator {
         arena: UnsafeCell<[u8; ARENA_SIZE]>,
         remaining: AtomicUsize, // we allocate from the top, counting down
+        // Bookkeeping for in-place `realloc` of the most recent allocation: the offset it was
+        // placed at, and the exclusive offset it may extend up to (the value `remaining` held
+        // just before this allocation, i.e. any slack left by that allocation's alignment
+        // rounding). Space above that limit belongs to an older, still-live allocation.
+        last_alloc_offset: AtomicUsize,
+        last_alloc_limit: AtomicUsize,
+        // Which thread, if any, currently owns a run of nested `Arena` `Janitor`s: 0 means
+        // unowned, otherwise a live thread's `current_thread_token()`. `owner_depth` counts how many
+        // of that thread's `Janitor`s are still open. `remaining`'s checkpoint/restore dance is
+        // only sound within a single thread's nesting, so a second thread trying to open an
+        // `Arena` `Janitor` while another thread already owns one is a contract violation and
+        // must be rejected rather than silently corrupt whichever allocation rewinds out from
+        // under it.
+        owner_thread: AtomicU64,
+        owner_depth: AtomicUsize,
     }
 
     impl ArenaAllocator {
+        pub fn new() -> Self {
+            Self {
+                arena: UnsafeCell::new([0x55; ARENA_SIZE]),
+                remaining: AtomicUsize::new(ARENA_SIZE),
+                last_alloc_offset: AtomicUsize::new(ARENA_NO_LAST_ALLOC),
+                last_alloc_limit: AtomicUsize::new(0),
+                owner_thread: AtomicU64::new(0),
+                owner_depth: AtomicUsize::new(0),
+            }
+        }
+
+        // Claims ownership of the arena's checkpoint/restore nesting for the calling thread,
+        // panicking if another thread already owns it. Reentrant: a thread that already owns the
+        // arena just bumps its nesting depth.
+        fn claim_owner(&self) {
+            let this_thread = current_thread_token();
+            loop {
+                let owner = self.owner_thread.load(Acquire);
+                if owner == this_thread {
+                    self.owner_depth.fetch_add(1, AcqRel);
+                    return;
+                }
+                if owner != 0 {
+                    panic!(
+                        "ArenaAllocator: an Arena Janitor is already open on another thread; \
+                         concurrent cross-thread Arena scopes would corrupt the checkpoint/restore \
+                         invariant"
+                    );
+                }
+                if self
+                    .owner_thread
+                    .compare_exchange(0, this_thread, AcqRel, Acquire)
+                    .is_ok()
+                {
+                    self.owner_depth.store(1, Release);
+                    return;
+                }
+                // Lost the race to claim an unowned arena; re-check who won.
+            }
+        }
+
+        // Releases one level of the calling thread's nesting, handing the arena back to
+        // "unowned" once its outermost `Janitor` drops.
+        fn release_owner(&self) {
+            if self.owner_depth.fetch_sub(1, AcqRel) == 1 {
+                self.owner_thread.store(0, Release);
+            }
+        }
+
         unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
             let size = layout.size();
             let align = layout.align();
@@ -60,12 +202,14 @@ pub mod mem {
             }
 
             let mut allocated = 0;
+            let mut limit = 0;
             if self
                 .remaining
                 .fetch_update(SeqCst, SeqCst, |mut remaining| {
                     if size > remaining {
                         return None;
                     }
+                    limit = remaining;
                     remaining -= size;
                     remaining &= align_mask_to_round_down;
                     allocated = remaining;
@@ -75,104 +219,448 @@ pub mod mem {
             {
                 return null_mut();
             };
+
+            self.last_alloc_offset.store(allocated, Release);
+            self.last_alloc_limit.store(limit, Release);
+
             (self.arena.get() as *mut u8).add(allocated)
         }
         unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+
+        // Grows or shrinks `ptr` in place when it is the top-most (most recently handed out)
+        // allocation, returning the same pointer with no copy. Shrinking always fits, since the
+        // block's start never moves; growing only fits within whatever alignment slack the
+        // original allocation left above it, since beyond that belongs to an older allocation.
+        unsafe fn try_realloc_in_place(&self, ptr: *mut u8, new_size: usize) -> Option<*mut u8> {
+            let offset = ptr.offset_from(self.arena.get() as *mut u8) as usize;
+
+            if self.last_alloc_offset.load(Acquire) != offset {
+                return None;
+            }
+
+            if offset + new_size > self.last_alloc_limit.load(Acquire) {
+                return None;
+            }
+
+            // Re-check under a CAS that nothing has allocated since: `remaining` should still
+            // equal `offset`, i.e. this allocation is still the bump pointer's position.
+            self.remaining
+                .compare_exchange(offset, offset, AcqRel, Acquire)
+                .ok()?;
+
+            Some(ptr)
+        }
+
+        // Whether `ptr` falls inside this arena's backing buffer, so the top-level
+        // `GlobalAlloc::dealloc` can route a pointer to the allocator that owns it.
+        fn contains(&self, ptr: *mut u8) -> bool {
+            let base = self.arena.get() as *mut u8 as usize;
+            let addr = ptr as usize;
+            addr >= base && addr < base + ARENA_SIZE
+        }
+    }
+
+    impl Default for ArenaAllocator {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    unsafe impl Allocator for ArenaAllocator {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            if layout.size() == 0 {
+                let ptr = NonNull::new(layout.align() as *mut u8).ok_or(AllocError)?;
+                return Ok(NonNull::slice_from_raw_parts(ptr, 0));
+            }
+
+            let ptr = NonNull::new(unsafe { self.alloc(layout) }).ok_or(AllocError)?;
+            Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            // `allocate` hands back a fake dangling `layout.align()`-as-address pointer for
+            // zero-size layouts instead of a real arena address; `dealloc` is a no-op here so
+            // routing that fake pointer through it is harmless, but skip it explicitly anyway so
+            // this doesn't rely on that coincidence.
+            if layout.size() == 0 {
+                return;
+            }
+            self.dealloc(ptr.as_ptr(), layout);
+        }
     }
 
     const POOL_SIZE: usize = 128 * 1024;
-    const POOL_MAX_SUPPORTED_ALIGN: usize = 4096;
+    // Blocks are threaded with an intrusive next-free index in their first `usize`, so a block
+    // must be at least that big, and also the largest alignment we can promise a caller, since
+    // each block's offset from the (4096-aligned) backing buffer is a multiple of `BLOCK_SIZE`.
+    const BLOCK_SIZE: usize = 256;
+    const POOL_MAX_SUPPORTED_ALIGN: usize = BLOCK_SIZE;
+    const POOL_BLOCK_COUNT: usize = POOL_SIZE / BLOCK_SIZE;
+    // Sentinel stored in a block's free-list link to mark the end of the chain.
+    const POOL_FREE_LIST_END: usize = usize::MAX;
+
+    // `free_list_head` packs a block index together with a generation tag into one word, CAS'd
+    // together as a unit, to defeat the ABA problem on the free-list pop below: without a tag,
+    // a block popped by one thread, freed, and popped again by another thread can land back at
+    // the same index a stalled competing CAS still expects, which would splice a live block back
+    // onto the free list. The index occupies the low bits; index `POOL_BLOCK_COUNT` (one past the
+    // last real index) stands for "list empty" in this packed form, distinct from the
+    // `POOL_FREE_LIST_END` sentinel blocks store in their own on-disk links.
+    const POOL_HEAD_INDEX_BITS: u32 = 16;
+    const POOL_HEAD_INDEX_MASK: usize = (1 << POOL_HEAD_INDEX_BITS) - 1;
+    const _: () = assert!(POOL_BLOCK_COUNT < POOL_HEAD_INDEX_MASK);
+
+    fn pack_head(generation: usize, index: usize) -> usize {
+        (generation << POOL_HEAD_INDEX_BITS) | index
+    }
 
+    // Returns `(generation, index)`, where `index == POOL_BLOCK_COUNT` means "list empty".
+    fn unpack_head(packed: usize) -> (usize, usize) {
+        (packed >> POOL_HEAD_INDEX_BITS, packed & POOL_HEAD_INDEX_MASK)
+    }
+
+    // Builds the pool's backing buffer with every block already threaded onto the free list:
+    // block `i`'s first `usize` holds the index of block `i + 1`, and the last block holds
+    // `POOL_FREE_LIST_END`. Doing this as a const fn lets the free list exist from the first
+    // instant the `static` is initialised, with no runtime construction step.
+    const fn build_pool_buffer() -> [u8; POOL_SIZE] {
+        let mut buf = [0x55u8; POOL_SIZE];
+
+        let mut index = 0;
+        while index < POOL_BLOCK_COUNT {
+            let next = if index + 1 < POOL_BLOCK_COUNT {
+                index + 1
+            } else {
+                POOL_FREE_LIST_END
+            };
+            let next_bytes = next.to_ne_bytes();
+
+            let offset = index * BLOCK_SIZE;
+            let mut byte = 0;
+            while byte < next_bytes.len() {
+                buf[offset + byte] = next_bytes[byte];
+                byte += 1;
+            }
+
+            index += 1;
+        }
+
+        buf
+    }
+
+    /// A fixed-size-block pool allocator over its own private 128 KiB buffer, recycling blocks
+    /// through an intrusive free list. Like [`ArenaAllocator`], construct it with [`Self::new`]
+    /// and pass it to `Vec::new_in`/`Box::new_in` (etc.) to confine same-sized allocations to
+    /// the pool without hijacking global allocation.
     #[derive(Debug)]
     #[repr(C, align(4096))] // 4096 == MAX_SUPPORTED_ALIGN
-    struct PoolAllocator {
+    pub struct PoolAllocator {
         pool: UnsafeCell<[u8; POOL_SIZE]>,
-        remaining: AtomicUsize, // we allocate from the top, counting down
+        // A generation-tagged `(generation, index)` pair, see `pack_head`/`unpack_head`. Starts
+        // at `pack_head(0, 0)`, which happens to equal `0`.
+        free_list_head: AtomicUsize,
+        // Bytes currently handed out, maintained incrementally in `alloc`/`dealloc` so reading it
+        // (e.g. for a peak-tracking update on every allocation) doesn't need to walk the free
+        // list, unlike `free_block_count`.
+        bytes_in_use: AtomicUsize,
     }
 
     impl PoolAllocator {
-        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-            let size = layout.size();
-            let align = layout.align();
+        pub fn new() -> Self {
+            Self {
+                pool: UnsafeCell::new(build_pool_buffer()),
+                free_list_head: AtomicUsize::new(0),
+                bytes_in_use: AtomicUsize::new(0),
+            }
+        }
 
-            // `Layout` contract forbids making a `Layout` with align=0, or align not power of 2.
-            // So we can safely use a mask to ensure alignment without worrying about UB.
-            let align_mask_to_round_down = !(align - 1);
+        unsafe fn block_link(&self, index: usize) -> *mut usize {
+            (self.pool.get() as *mut u8).add(index * BLOCK_SIZE) as *mut usize
+        }
 
-            if align > POOL_MAX_SUPPORTED_ALIGN {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if layout.size() > BLOCK_SIZE || layout.align() > POOL_MAX_SUPPORTED_ALIGN {
                 return null_mut();
             }
 
-            let mut allocated = 0;
-            if self
-                .remaining
-                .fetch_update(SeqCst, SeqCst, |mut remaining| {
-                    if size > remaining {
-                        return None;
-                    }
-                    remaining -= size;
-                    remaining &= align_mask_to_round_down;
-                    allocated = remaining;
-                    Some(remaining)
-                })
-                .is_err()
-            {
-                return null_mut();
+            let mut packed = self.free_list_head.load(Acquire);
+            let head = loop {
+                let (generation, head) = unpack_head(packed);
+                if head == POOL_BLOCK_COUNT {
+                    return null_mut();
+                }
+
+                let next = *self.block_link(head);
+                let next = if next == POOL_FREE_LIST_END {
+                    POOL_BLOCK_COUNT
+                } else {
+                    next
+                };
+                let bumped = pack_head(generation.wrapping_add(1), next);
+
+                match self
+                    .free_list_head
+                    .compare_exchange_weak(packed, bumped, AcqRel, Acquire)
+                {
+                    Ok(_) => break head,
+                    Err(actual) => packed = actual,
+                }
             };
-            (self.pool.get() as *mut u8).add(allocated)
+
+            self.bytes_in_use.fetch_add(BLOCK_SIZE, SeqCst);
+
+            (self.pool.get() as *mut u8).add(head * BLOCK_SIZE)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+            let base = self.pool.get() as *mut u8;
+            let index = ptr.offset_from(base) as usize / BLOCK_SIZE;
+
+            let mut packed = self.free_list_head.load(Acquire);
+            loop {
+                let (generation, head) = unpack_head(packed);
+                *(ptr as *mut usize) = if head == POOL_BLOCK_COUNT {
+                    POOL_FREE_LIST_END
+                } else {
+                    head
+                };
+
+                let bumped = pack_head(generation.wrapping_add(1), index);
+                match self
+                    .free_list_head
+                    .compare_exchange_weak(packed, bumped, AcqRel, Acquire)
+                {
+                    Ok(_) => break,
+                    Err(actual) => packed = actual,
+                }
+            }
+
+            self.bytes_in_use.fetch_sub(BLOCK_SIZE, SeqCst);
+        }
+
+        // Walks the free list to report how many blocks are currently unused. Only intended for
+        // the best-effort snapshot in `AllocatorManager::info`, not a hot path.
+        fn free_block_count(&self) -> usize {
+            let mut count = 0;
+            let (_, mut index) = unpack_head(self.free_list_head.load(Acquire));
+            while index != POOL_BLOCK_COUNT {
+                count += 1;
+                index = unsafe {
+                    let next = *self.block_link(index);
+                    if next == POOL_FREE_LIST_END {
+                        POOL_BLOCK_COUNT
+                    } else {
+                        next
+                    }
+                };
+            }
+            count
+        }
+
+        // Whether `ptr` falls inside this pool's backing buffer, so the top-level
+        // `GlobalAlloc::dealloc` can route a pointer to the allocator that owns it.
+        fn contains(&self, ptr: *mut u8) -> bool {
+            let base = self.pool.get() as *mut u8 as usize;
+            let addr = ptr as usize;
+            addr >= base && addr < base + POOL_SIZE
+        }
+    }
+
+    impl Default for PoolAllocator {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    unsafe impl Allocator for PoolAllocator {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            if layout.size() == 0 {
+                let ptr = NonNull::new(layout.align() as *mut u8).ok_or(AllocError)?;
+                return Ok(NonNull::slice_from_raw_parts(ptr, 0));
+            }
+
+            let ptr = NonNull::new(unsafe { self.alloc(layout) }).ok_or(AllocError)?;
+            // Every block is the same size, so the caller gets the whole block, not just the
+            // slice it asked for.
+            Ok(NonNull::slice_from_raw_parts(ptr, BLOCK_SIZE))
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            // `allocate` hands back a fake dangling `layout.align()`-as-address pointer for
+            // zero-size layouts, which was never threaded onto the free list. Feeding it to
+            // `dealloc` would compute a bogus block index from that fake address and write
+            // through it, corrupting the free list (or, at worst, crashing outright on a
+            // misaligned write). Skip it, mirroring `allocate`'s own special case.
+            if layout.size() == 0 {
+                return;
+            }
+            self.dealloc(ptr.as_ptr(), layout);
         }
-        unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
     }
 
     #[derive(Debug)]
     pub struct AllocatorManager {
-        ctx: [AllocationContext; 1024],
-        ctx_ptr: AtomicUsize,
         system: SystemAllocator,
         arena: ArenaAllocator,
         pool: PoolAllocator,
+        // How many allocations exhausted the arena/pool and spilled over to `system` instead of
+        // failing outright.
+        arena_spilled: AtomicUsize,
+        pool_spilled: AtomicUsize,
+        // Per-context profiling: successful allocation counts, bytes-in-use high-water marks,
+        // and counts of allocations that failed outright, i.e. returned null. An arena/pool
+        // rejection (for size/align, or exhaustion) spills to `system` and is counted as spilled;
+        // it only counts as failed when `system` then returns null too.
+        system_alloc_count: AtomicUsize,
+        arena_alloc_count: AtomicUsize,
+        pool_alloc_count: AtomicUsize,
+        system_peak: AtomicUsize,
+        arena_peak: AtomicUsize,
+        pool_peak: AtomicUsize,
+        system_failed: AtomicUsize,
+        arena_failed: AtomicUsize,
+        pool_failed: AtomicUsize,
     }
 
     #[global_allocator]
     static mut ALLOCATOR: AllocatorManager = AllocatorManager {
-        ctx: [AllocationContext::System; 1024],
-        ctx_ptr: AtomicUsize::new(0),
-
         system: SystemAllocator {
             allocated: AtomicUsize::new(0),
         },
         arena: ArenaAllocator {
             arena: UnsafeCell::new([0x55; ARENA_SIZE]),
             remaining: AtomicUsize::new(ARENA_SIZE),
+            last_alloc_offset: AtomicUsize::new(ARENA_NO_LAST_ALLOC),
+            last_alloc_limit: AtomicUsize::new(0),
+            owner_thread: AtomicU64::new(0),
+            owner_depth: AtomicUsize::new(0),
         },
         pool: PoolAllocator {
-            pool: UnsafeCell::new([0x55; POOL_SIZE]),
-            remaining: AtomicUsize::new(POOL_SIZE),
+            pool: UnsafeCell::new(build_pool_buffer()),
+            free_list_head: AtomicUsize::new(0),
+            bytes_in_use: AtomicUsize::new(0),
         },
+        arena_spilled: AtomicUsize::new(0),
+        pool_spilled: AtomicUsize::new(0),
+        system_alloc_count: AtomicUsize::new(0),
+        arena_alloc_count: AtomicUsize::new(0),
+        pool_alloc_count: AtomicUsize::new(0),
+        system_peak: AtomicUsize::new(0),
+        arena_peak: AtomicUsize::new(0),
+        pool_peak: AtomicUsize::new(0),
+        system_failed: AtomicUsize::new(0),
+        arena_failed: AtomicUsize::new(0),
+        pool_failed: AtomicUsize::new(0),
     };
 
     unsafe impl Sync for AllocatorManager {}
 
     unsafe impl GlobalAlloc for AllocatorManager {
         unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-            match self.ctx[self.ctx_ptr.load(Acquire)] {
-                AllocationContext::Arena => self.arena.alloc(layout),
-                AllocationContext::Pool => self.pool.alloc(layout),
-                AllocationContext::System => self.system.alloc(layout),
+            let ctx = CONTEXT_STACK
+                .with(|stack| stack.borrow().top())
+                .unwrap_or(AllocationContext::System);
+
+            match ctx {
+                AllocationContext::Arena => {
+                    let ptr = self.arena.alloc(layout);
+                    if !ptr.is_null() {
+                        self.arena_alloc_count.fetch_add(1, SeqCst);
+                        update_peak(&self.arena_peak, ARENA_SIZE - self.arena.remaining.load(Acquire));
+                        return ptr;
+                    }
+                    // Arena is exhausted (or can't satisfy the alignment); spill to the system
+                    // allocator rather than let the caller hit the default OOM abort.
+                    self.arena_spilled.fetch_add(1, SeqCst);
+                    self.spill_to_system(layout, &self.arena_failed)
+                }
+                AllocationContext::Pool => {
+                    let ptr = self.pool.alloc(layout);
+                    if !ptr.is_null() {
+                        self.pool_alloc_count.fetch_add(1, SeqCst);
+                        update_peak(&self.pool_peak, self.pool.bytes_in_use.load(Acquire));
+                        return ptr;
+                    }
+                    self.pool_spilled.fetch_add(1, SeqCst);
+                    self.spill_to_system(layout, &self.pool_failed)
+                }
+                AllocationContext::System => {
+                    let ptr = self.system.alloc(layout);
+                    if ptr.is_null() {
+                        self.system_failed.fetch_add(1, SeqCst);
+                    } else {
+                        self.system_alloc_count.fetch_add(1, SeqCst);
+                        update_peak(&self.system_peak, self.system.allocated.load(Acquire));
+                    }
+                    ptr
+                }
             }
         }
 
-        unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            // Route by address, not by the currently active context: a pointer allocated inside
+            // one `Janitor` scope may well be freed after that scope (and its context) is gone,
+            // and a spilled-to-system allocation must always reach `System.dealloc` regardless
+            // of which context is active when it's dropped.
+            if self.arena.contains(ptr) {
+                self.arena.dealloc(ptr, layout);
+            } else if self.pool.contains(ptr) {
+                self.pool.dealloc(ptr, layout);
+            } else {
+                self.system.dealloc(ptr, layout);
+            }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            // Route by whether `ptr` actually lives in the arena's buffer, not by whichever
+            // context happens to be active on this thread right now (see `dealloc`, which routes
+            // the same way): an unrelated `Pool`/`System` allocation could be growing while an
+            // `Arena` Janitor happens to be open on this thread, and `try_realloc_in_place`'s
+            // `offset_from` is UB on a pointer outside the arena's allocation.
+            if self.arena.contains(ptr) {
+                if let Some(ptr) = self.arena.try_realloc_in_place(ptr, new_size) {
+                    return ptr;
+                }
+            }
+
+            // Default path: allocate fresh, copy what still fits, free the old pointer.
+            let new_layout = match Layout::from_size_align(new_size, layout.align()) {
+                Ok(new_layout) => new_layout,
+                Err(_) => return null_mut(),
+            };
+
+            let new_ptr = self.alloc(new_layout);
+            if !new_ptr.is_null() {
+                std::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
+            }
+            new_ptr
+        }
     }
 
     impl AllocatorManager {
-        fn push_allocator(&mut self, ctx: AllocationContext) {
-            let idx = self.ctx_ptr.fetch_add(1, SeqCst);
-            self.ctx[idx + 1] = ctx; // Add 1 because fetch-add returns the previous value.
+        fn push_allocator(&self, ctx: AllocationContext) {
+            CONTEXT_STACK.with(|stack| stack.borrow_mut().push(ctx));
         }
 
-        fn pop_allocator(&mut self) {
-            self.ctx_ptr.fetch_sub(1, SeqCst);
+        fn pop_allocator(&self) {
+            CONTEXT_STACK.with(|stack| {
+                stack.borrow_mut().pop();
+            });
+        }
+
+        // Shared tail of the arena/pool alloc paths once their own allocator has failed:
+        // try the system allocator, and record the outcome in whichever `failed` counter
+        // belongs to the context that just ran out of room.
+        unsafe fn spill_to_system(&self, layout: Layout, failed: &AtomicUsize) -> *mut u8 {
+            let ptr = self.system.alloc(layout);
+            if ptr.is_null() {
+                failed.fetch_add(1, SeqCst);
+            } else {
+                self.system_alloc_count.fetch_add(1, SeqCst);
+                update_peak(&self.system_peak, self.system.allocated.load(Acquire));
+            }
+            ptr
         }
 
         pub fn info() -> AllocationInfo {
@@ -181,15 +669,29 @@ pub mod mem {
             let arena_remaining = unsafe { ALLOCATOR.arena.remaining.load(Acquire) };
             let arena_allocated = ARENA_SIZE - arena_remaining;
 
-            let pool_remaining = unsafe { ALLOCATOR.pool.remaining.load(Acquire) };
+            let pool_remaining = unsafe { ALLOCATOR.pool.free_block_count() } * BLOCK_SIZE;
             let pool_allocated = POOL_SIZE - pool_remaining;
 
+            let arena_spilled_to_system = unsafe { ALLOCATOR.arena_spilled.load(Acquire) };
+            let pool_spilled_to_system = unsafe { ALLOCATOR.pool_spilled.load(Acquire) };
+
             AllocationInfo {
                 system_allocated,
                 arena_allocated,
                 arena_remaining,
                 pool_allocated,
                 pool_remaining,
+                arena_spilled_to_system,
+                pool_spilled_to_system,
+                system_alloc_count: unsafe { ALLOCATOR.system_alloc_count.load(Acquire) },
+                arena_alloc_count: unsafe { ALLOCATOR.arena_alloc_count.load(Acquire) },
+                pool_alloc_count: unsafe { ALLOCATOR.pool_alloc_count.load(Acquire) },
+                system_peak: unsafe { ALLOCATOR.system_peak.load(Acquire) },
+                arena_peak: unsafe { ALLOCATOR.arena_peak.load(Acquire) },
+                pool_peak: unsafe { ALLOCATOR.pool_peak.load(Acquire) },
+                system_failed: unsafe { ALLOCATOR.system_failed.load(Acquire) },
+                arena_failed: unsafe { ALLOCATOR.arena_failed.load(Acquire) },
+                pool_failed: unsafe { ALLOCATOR.pool_failed.load(Acquire) },
             }
         }
     }
@@ -201,21 +703,263 @@ pub mod mem {
         arena_remaining: usize,
         pool_allocated: usize,
         pool_remaining: usize,
+        // How many allocations couldn't be satisfied by the arena/pool and spilled to `system`.
+        arena_spilled_to_system: usize,
+        pool_spilled_to_system: usize,
+        // Per-context profiling, see `AllocatorManager`'s fields of the same names.
+        system_alloc_count: usize,
+        arena_alloc_count: usize,
+        pool_alloc_count: usize,
+        system_peak: usize,
+        arena_peak: usize,
+        pool_peak: usize,
+        system_failed: usize,
+        arena_failed: usize,
+        pool_failed: usize,
     }
 
-    pub struct Janitor;
+    impl Display for AllocationInfo {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write(
+                f,
+                format_args!(
+                    "system: {} bytes in use (peak {}), {} allocs, {} failed\n\
+                     arena:  {} bytes in use (peak {}) of {} ({} remaining), {} allocs, {} failed, {} spilled\n\
+                     pool:   {} bytes in use (peak {}) of {} ({} remaining), {} allocs, {} failed, {} spilled",
+                    self.system_allocated,
+                    self.system_peak,
+                    self.system_alloc_count,
+                    self.system_failed,
+                    self.arena_allocated,
+                    self.arena_peak,
+                    ARENA_SIZE,
+                    self.arena_remaining,
+                    self.arena_alloc_count,
+                    self.arena_failed,
+                    self.arena_spilled_to_system,
+                    self.pool_allocated,
+                    self.pool_peak,
+                    POOL_SIZE,
+                    self.pool_remaining,
+                    self.pool_alloc_count,
+                    self.pool_failed,
+                    self.pool_spilled_to_system,
+                ),
+            )
+        }
+    }
+
+    /// A scoped guard that activates an [`AllocationContext`] for its lifetime and, for `Arena`,
+    /// rewinds the region to its checkpoint on drop. `Pool` needs no such rewind: its blocks are
+    /// genuinely recycled through `dealloc`, so letting individual allocations free themselves
+    /// is correct without a region-wide reset. `dealloc` routes by address, so a block goes back
+    /// onto the pool's free list even when its owner is dropped under another context.
+    ///
+    /// # Safety contract
+    ///
+    /// Any pointer handed out by the global allocator while a `Janitor` for `Arena` is live
+    /// (directly, or via a collection allocating under this context) must not be dereferenced
+    /// after that `Janitor` is dropped: dropping restores `remaining` to the checkpoint taken in
+    /// `new`, so the memory behind the pointer may be handed out again to a subsequent
+    /// allocation. Nested `Janitor`s must be dropped in LIFO order; nothing checks this, and
+    /// dropping a `Janitor` pops whichever context is on top of the current thread's stack.
+    ///
+    /// The arena's checkpoint/restore dance is inherently single-owner: it is only sound if all
+    /// of a run of nested `Arena` `Janitor`s belong to the same thread, since `remaining` is
+    /// shared state and an unrelated thread's allocation could be rewound out from under it
+    /// otherwise. This is enforced at runtime — opening an `Arena` `Janitor` while another thread
+    /// already has one open **panics** rather than silently racing. `Pool` and `System` have no
+    /// such restriction: the pool's blocks are reclaimed independently through `dealloc`, and
+    /// `System` never rewinds anything.
+    pub struct Janitor {
+        ctx: AllocationContext,
+        checkpoint: Option<usize>,
+    }
 
     impl Janitor {
         pub fn new(ctx: AllocationContext) -> Self {
+            let checkpoint = match ctx {
+                AllocationContext::Arena => {
+                    // Panics if another thread already owns a run of `Arena` `Janitor`s; see the
+                    // safety contract above.
+                    unsafe { ALLOCATOR.arena.claim_owner() };
+                    Some(unsafe { ALLOCATOR.arena.remaining.load(Acquire) })
+                }
+                AllocationContext::Pool | AllocationContext::System => None,
+            };
+
             unsafe { ALLOCATOR.push_allocator(ctx) };
 
-            Self
+            Self { ctx, checkpoint }
         }
     }
 
     impl Drop for Janitor {
         fn drop(&mut self) {
             unsafe { ALLOCATOR.pop_allocator() };
+
+            if let (AllocationContext::Arena, Some(checkpoint)) = (self.ctx, self.checkpoint) {
+                unsafe {
+                    ALLOCATOR.arena.remaining.store(checkpoint, Release);
+                    ALLOCATOR.arena.release_owner();
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::panic;
+        use std::sync::{Arc, Barrier, Mutex};
+        use std::thread;
+
+        // The arena and pool are shared global state (they back `#[global_allocator]`), so
+        // tests that drive them through the real `ALLOCATOR` (rather than a standalone
+        // instance) must not run concurrently with each other, or they'll either spuriously
+        // trip the arena's owner-thread assertion or see each other's pool allocations.
+        static ALLOCATOR_TEST_GUARD: Mutex<()> = Mutex::new(());
+
+        #[test]
+        fn nested_arena_janitors_restore_the_outer_checkpoint() {
+            let _guard = ALLOCATOR_TEST_GUARD.lock().unwrap();
+            let before = unsafe { ALLOCATOR.arena.remaining.load(Acquire) };
+
+            {
+                let _outer = Janitor::new(AllocationContext::Arena);
+                let _v: Vec<u8> = Vec::with_capacity(64);
+                {
+                    let _inner = Janitor::new(AllocationContext::Arena);
+                    let _v2: Vec<u8> = Vec::with_capacity(64);
+                }
+            }
+
+            let after = unsafe { ALLOCATOR.arena.remaining.load(Acquire) };
+            assert_eq!(before, after);
+        }
+
+        #[test]
+        fn arena_janitor_rejects_a_second_thread_while_one_is_open() {
+            let _guard = ALLOCATOR_TEST_GUARD.lock().unwrap();
+            let claimed = Arc::new(Barrier::new(2));
+            let release = Arc::new(Barrier::new(2));
+            let (claimed2, release2) = (Arc::clone(&claimed), Arc::clone(&release));
+
+            let other = thread::spawn(move || {
+                let _j = Janitor::new(AllocationContext::Arena);
+                claimed2.wait();
+                release2.wait();
+            });
+
+            claimed.wait();
+            let result = panic::catch_unwind(|| Janitor::new(AllocationContext::Arena));
+            assert!(result.is_err());
+
+            release.wait();
+            other.join().unwrap();
+
+            // Now that the owning thread's Janitor has dropped, this thread can claim it.
+            let _j = Janitor::new(AllocationContext::Arena);
+        }
+
+        #[test]
+        fn pool_allocator_reuses_freed_blocks() {
+            let pool = PoolAllocator::new();
+            let layout = Layout::new::<[u8; 64]>();
+
+            let first = pool.allocate(layout).unwrap().as_ptr() as *mut u8;
+            unsafe { pool.deallocate(NonNull::new(first).unwrap(), layout) };
+
+            // Freeing a block and immediately reallocating the same size must hand back the
+            // block just freed, since it's now the head of the free list.
+            let second = pool.allocate(layout).unwrap().as_ptr() as *mut u8;
+            assert_eq!(first, second);
+        }
+
+        #[test]
+        fn arena_try_realloc_in_place_respects_alignment_slack() {
+            let arena = ArenaAllocator::new();
+            // Aligning 5 bytes up to 8 rounds the bump pointer down 3 bytes further than the
+            // request needed, leaving 3 bytes of slack above this allocation.
+            let layout = Layout::from_size_align(5, 8).unwrap();
+
+            let ptr = unsafe { arena.alloc(layout) };
+            assert!(!ptr.is_null());
+
+            // Growing into that slack in place is sound: nothing else has claimed it yet.
+            let grown = unsafe { arena.try_realloc_in_place(ptr, 8) };
+            assert_eq!(grown, Some(ptr));
+
+            // Shrinking always fits in place, since the block's start never moves.
+            let shrunk = unsafe { arena.try_realloc_in_place(ptr, 4) };
+            assert_eq!(shrunk, Some(ptr));
+
+            // Growing past the slack fails: that space belongs to whatever was (or, here,
+            // would be) allocated before this one.
+            let too_big = unsafe { arena.try_realloc_in_place(ptr, 9) };
+            assert_eq!(too_big, None);
+        }
+
+        #[test]
+        fn pool_exhaustion_spills_to_system_and_dealloc_routes_correctly() {
+            let _guard = ALLOCATOR_TEST_GUARD.lock().unwrap();
+
+            // Allocated up front, outside the `Pool` Janitor below, so growing this Vec's own
+            // backing buffer doesn't itself count as a pool allocation (or spill).
+            let mut blocks = Vec::with_capacity(POOL_BLOCK_COUNT + 1);
+            let spilled_before = unsafe { ALLOCATOR.pool_spilled.load(Acquire) };
+
+            {
+                let _j = Janitor::new(AllocationContext::Pool);
+
+                // Exhaust the pool completely.
+                for _ in 0..POOL_BLOCK_COUNT {
+                    blocks.push(Box::new([0u8; BLOCK_SIZE]));
+                }
+
+                // One more allocation has nowhere left in the pool to go, so it must spill to the
+                // system allocator instead of failing outright.
+                let spilled = Box::new([0u8; BLOCK_SIZE]);
+                let spilled_ptr = &*spilled as *const [u8; BLOCK_SIZE] as *mut u8;
+                assert!(!unsafe { ALLOCATOR.pool.contains(spilled_ptr) });
+
+                // Dropping it here, while still routed by address rather than active context,
+                // must reach `System::dealloc` rather than corrupting the pool's free list.
+                drop(spilled);
+            }
+
+            let spilled_after = unsafe { ALLOCATOR.pool_spilled.load(Acquire) };
+            assert_eq!(spilled_after, spilled_before + 1);
+        }
+
+        #[test]
+        fn pool_peak_tracks_bytes_in_use_without_a_free_list_walk() {
+            let _guard = ALLOCATOR_TEST_GUARD.lock().unwrap();
+
+            let (peak, bytes_in_use) = {
+                let _j = Janitor::new(AllocationContext::Pool);
+                let _a = Box::new([0u8; BLOCK_SIZE]);
+                let _b = Box::new([0u8; BLOCK_SIZE]);
+                let bytes_in_use = unsafe { ALLOCATOR.pool.bytes_in_use.load(Acquire) };
+                (AllocatorManager::info().pool_peak, bytes_in_use)
+            };
+
+            // The peak must reflect the incrementally-tracked counter, not lag behind it (as it
+            // would if something still recomputed it by walking the free list on a stale cadence).
+            assert!(bytes_in_use >= 2 * BLOCK_SIZE);
+            assert!(peak >= bytes_in_use);
+        }
+
+        #[test]
+        fn allocation_info_display_surfaces_remaining_capacity() {
+            let _guard = ALLOCATOR_TEST_GUARD.lock().unwrap();
+            let info = AllocatorManager::info();
+            let rendered = format!("{}", info);
+
+            // Catches `arena_remaining`/`pool_remaining` regressing back to dead fields: the
+            // rendered text must contain these exact values, not just the word "remaining".
+            assert!(rendered.contains(&format!("{} remaining", info.arena_remaining)));
+            assert!(rendered.contains(&format!("{} remaining", info.pool_remaining)));
         }
     }
 }